@@ -2,6 +2,15 @@ use anchor_lang::prelude::*;
 
 declare_id!("9MGNGbBKQqxDhVkRxuH5qDyovnpUw1FviYEUNcN7WUD");
 
+const LOYALTY_POINTS_PER_VISIT: u64 = 10;
+const LOYALTY_POINTS_PER_DISH: u64 = 2;
+
+// Bayesian prior blended into a restaurant's aggregate rating so a single
+// early high-confidence review can't dominate the score before enough
+// reviews have accumulated. See RestaurantStats::average_times_100.
+const DEFAULT_PRIOR_MEAN: u64 = 3;
+const DEFAULT_PRIOR_WEIGHT: u64 = 10;
+
 #[program]
 pub mod restaurant_booking {
     use super::*;
@@ -30,62 +39,279 @@ pub mod restaurant_booking {
         Ok(())
     }
 
-    pub fn book_table(ctx: Context<BookTable>, _dish_ids: Vec<Pubkey>) -> Result<()> {
+    pub fn initialize_loyalty_account(ctx: Context<InitializeLoyaltyAccount>) -> Result<()> {
+        let loyalty_account = &mut ctx.accounts.loyalty_account;
+        loyalty_account.user = ctx.accounts.user.key();
+        loyalty_account.restaurant = ctx.accounts.restaurant.key();
+        loyalty_account.points = 0;
+        Ok(())
+    }
+
+    pub fn transfer_points(ctx: Context<TransferPoints>, amount: u64) -> Result<()> {
+        require!(
+            ctx.accounts.sender.points >= amount,
+            ErrorCode::InsufficientLoyaltyPoints
+        );
+
+        let sender = &mut ctx.accounts.sender;
+        sender.points = sender
+            .points
+            .checked_sub(amount)
+            .ok_or(ErrorCode::InsufficientLoyaltyPoints)?;
+
+        let receiver = &mut ctx.accounts.receiver;
+        receiver.points = receiver
+            .points
+            .checked_add(amount)
+            .ok_or(ErrorCode::LoyaltyPointsOverflow)?;
+
+        Ok(())
+    }
+
+    pub fn initialize_restaurant_stats(ctx: Context<InitializeRestaurantStats>) -> Result<()> {
+        let stats = &mut ctx.accounts.restaurant_stats;
+        stats.restaurant = ctx.accounts.restaurant.key();
+        stats.weight_total = 0;
+        stats.weighted_sum = 0;
+        stats.prior_mean = DEFAULT_PRIOR_MEAN;
+        stats.prior_weight = DEFAULT_PRIOR_WEIGHT;
+        recompute_average(stats)?;
+        Ok(())
+    }
+
+    pub fn book_table(ctx: Context<BookTable>, dish_ids: Vec<Pubkey>) -> Result<()> {
         let user_stats = &mut ctx.accounts.user_stats;
         user_stats.visit_count += 1;
 
-        for i in (0..ctx.remaining_accounts.len()).step_by(2) {
-            if i >= ctx.remaining_accounts.len() {
-                continue;
-            }
+        let bonus = (dish_ids.len() as u64)
+            .checked_mul(LOYALTY_POINTS_PER_DISH)
+            .ok_or(ErrorCode::LoyaltyPointsOverflow)?;
+        let earned = LOYALTY_POINTS_PER_VISIT
+            .checked_add(bonus)
+            .ok_or(ErrorCode::LoyaltyPointsOverflow)?;
 
-            let dish_stats_info = &ctx.remaining_accounts[i];
+        let loyalty_account = &mut ctx.accounts.loyalty_account;
+        loyalty_account.points = loyalty_account
+            .points
+            .checked_add(earned)
+            .ok_or(ErrorCode::LoyaltyPointsOverflow)?;
 
-            // First get a read-only reference to deserialize
-            let account_data = dish_stats_info.try_borrow_data()?;
-            let mut dish_stats = DishStats::try_deserialize(&mut &account_data[..])?;
+        let user_key = ctx.accounts.user.key();
 
-            // Update the count in our local copy
-            dish_stats.count += 1;
+        for dish_id in dish_ids.iter() {
+            let (expected_key, _bump) = Pubkey::find_program_address(
+                &[b"dish-stats", user_key.as_ref(), dish_id.as_ref()],
+                ctx.program_id,
+            );
+
+            let dish_stats_info = ctx
+                .remaining_accounts
+                .iter()
+                .find(|info| info.key() == expected_key)
+                .ok_or(ErrorCode::UnauthorizedDishStats)?;
 
-            // Drop the read-only reference
-            drop(account_data);
+            require_keys_eq!(
+                *dish_stats_info.owner,
+                *ctx.program_id,
+                ErrorCode::UnauthorizedDishStats
+            );
 
-            // Now get a mutable reference to write back
-            let mut account_data_mut = dish_stats_info.try_borrow_mut_data()?;
+            let mut dish_stats: Account<DishStats> = Account::try_from(dish_stats_info)?;
+            require_keys_eq!(dish_stats.user, user_key, ErrorCode::UnauthorizedDishStats);
 
-            // Write the data back
-            let mut writer = std::io::Cursor::new(&mut account_data_mut[..]);
-            dish_stats.try_serialize(&mut writer)?;
+            dish_stats.count += 1;
+            dish_stats.exit(ctx.program_id)?;
         }
 
         Ok(())
     }
 
-    pub fn submit_review(ctx: Context<SubmitReview>, rating: u8, review: String, confidence_level: u8) -> Result<()> {
+    pub fn submit_review(
+        ctx: Context<SubmitReview>,
+        rating: u8,
+        review: String,
+        confidence_level: u8,
+        text_len: u32,
+    ) -> Result<()> {
         require!(rating >= 1 && rating <= 5, ErrorCode::InvalidRating);
         require!(confidence_level >= 1 && confidence_level <= 10, ErrorCode::InvalidConfidenceLevel);
 
-        let review_account = &mut ctx.accounts.review;
+        let review_bytes = review.as_bytes();
+        require!(
+            text_len as usize == review_bytes.len(),
+            ErrorCode::TextLengthMismatch
+        );
 
-        // Only allow review if not already written
-        if review_account.review_len > 0 {
-            return Err(ErrorCode::ReviewAlreadyExists.into());
-        }
+        {
+            let mut review_account = ctx.accounts.review.load_init()?;
+            review_account.user = ctx.accounts.user.key();
+            review_account.restaurant = ctx.accounts.restaurant.key();
+            review_account.rating = rating;
+            review_account.confidence_level = confidence_level;
+            review_account.edit_count = 0;
+            review_account.last_updated = Clock::get()?.unix_timestamp;
+            review_account.text_len = review_bytes.len() as u32;
+        } // load_init ref must be dropped before review_text is touched
+
+        ctx.accounts.review_text.review = ctx.accounts.review.key();
+        ctx.accounts.review_text.data = review_bytes.to_vec();
+
+        let restaurant_stats = &mut ctx.accounts.restaurant_stats;
+        add_rating_contribution(restaurant_stats, rating, confidence_level)?;
+        recompute_average(restaurant_stats)?;
+
+        Ok(())
+    }
 
-        review_account.user = ctx.accounts.user.key();
-        review_account.restaurant = ctx.accounts.restaurant.key();
-        review_account.rating = rating;
-        review_account.confidence_level = confidence_level;
+    pub fn update_review(
+        ctx: Context<UpdateReview>,
+        rating: u8,
+        review: String,
+        confidence_level: u8,
+        text_len: u32,
+    ) -> Result<()> {
+        require!(rating >= 1 && rating <= 5, ErrorCode::InvalidRating);
+        require!(confidence_level >= 1 && confidence_level <= 10, ErrorCode::InvalidConfidenceLevel);
 
         let review_bytes = review.as_bytes();
-        let len = std::cmp::min(review_bytes.len(), 200);
-        review_account.review_len = len as u32;
-        review_account.review_data = [0u8; 200];
-        review_account.review_data[..len].copy_from_slice(&review_bytes[..len]);
+        require!(
+            text_len as usize == review_bytes.len(),
+            ErrorCode::TextLengthMismatch
+        );
+
+        let (old_rating, old_confidence_level) = {
+            let review_account = ctx.accounts.review.load()?;
+            (review_account.rating, review_account.confidence_level)
+        };
+
+        {
+            let mut review_account = ctx.accounts.review.load_mut()?;
+            review_account.rating = rating;
+            review_account.confidence_level = confidence_level;
+            review_account.edit_count += 1;
+            review_account.last_updated = Clock::get()?.unix_timestamp;
+            review_account.text_len = review_bytes.len() as u32;
+        } // load_mut ref must be dropped before review_text is rewritten
+
+        ctx.accounts.review_text.data = review_bytes.to_vec();
+
+        let restaurant_stats = &mut ctx.accounts.restaurant_stats;
+        subtract_rating_contribution(restaurant_stats, old_rating, old_confidence_level)?;
+        add_rating_contribution(restaurant_stats, rating, confidence_level)?;
+        recompute_average(restaurant_stats)?;
+
+        Ok(())
+    }
+
+    pub fn close_review(ctx: Context<CloseReview>) -> Result<()> {
+        let (rating, confidence_level) = {
+            let review_account = ctx.accounts.review.load()?;
+            (review_account.rating, review_account.confidence_level)
+        };
+
+        let restaurant_stats = &mut ctx.accounts.restaurant_stats;
+        subtract_rating_contribution(restaurant_stats, rating, confidence_level)?;
+        recompute_average(restaurant_stats)?;
 
         Ok(())
     }
+
+    pub fn follow_user(ctx: Context<FollowUser>, followed: bool) -> Result<()> {
+        let record = &mut ctx.accounts.follow_record;
+        record.follower = ctx.accounts.follower.key();
+        record.followee = ctx.accounts.followee.key();
+        record.followed = followed;
+        record.last_updated = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn set_petname(ctx: Context<SetPetname>, petname: String) -> Result<()> {
+        let record = &mut ctx.accounts.follow_record;
+        record.follower = ctx.accounts.follower.key();
+        record.followee = ctx.accounts.followee.key();
+
+        let petname_bytes = petname.as_bytes();
+        let len = std::cmp::min(petname_bytes.len(), 32);
+        record.petname_len = len as u32;
+        record.petname_data = [0u8; 32];
+        record.petname_data[..len].copy_from_slice(&petname_bytes[..len]);
+
+        record.last_updated = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    pub fn mute_user(ctx: Context<MuteUser>, muted: bool) -> Result<()> {
+        let record = &mut ctx.accounts.follow_record;
+        record.follower = ctx.accounts.follower.key();
+        record.followee = ctx.accounts.followee.key();
+        record.muted = muted;
+        record.last_updated = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+}
+
+// Folds a (rating, confidence_level) pair into the running weighted sums.
+fn add_rating_contribution(stats: &mut RestaurantStats, rating: u8, confidence_level: u8) -> Result<()> {
+    let weight = confidence_level as u128;
+    let weighted_rating = (rating as u128)
+        .checked_mul(weight)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+
+    stats.weighted_sum = stats
+        .weighted_sum
+        .checked_add(weighted_rating)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+    stats.weight_total = stats
+        .weight_total
+        .checked_add(weight)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+
+    Ok(())
+}
+
+// Removes a previously-folded-in (rating, confidence_level) pair, used by
+// update_review to undo the review's pre-image before applying the new one.
+fn subtract_rating_contribution(stats: &mut RestaurantStats, rating: u8, confidence_level: u8) -> Result<()> {
+    let weight = confidence_level as u128;
+    let weighted_rating = (rating as u128)
+        .checked_mul(weight)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+
+    stats.weighted_sum = stats
+        .weighted_sum
+        .checked_sub(weighted_rating)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+    stats.weight_total = stats
+        .weight_total
+        .checked_sub(weight)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+
+    Ok(())
+}
+
+// Recomputes average_times_100 from the running sums, blending in the
+// Bayesian prior so a cold-start restaurant isn't swung by one review:
+// (weighted_sum + C * m) / (weight_total + C), scaled by 100.
+fn recompute_average(stats: &mut RestaurantStats) -> Result<()> {
+    let prior_sum = (stats.prior_weight as u128)
+        .checked_mul(stats.prior_mean as u128)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+    let numerator = stats
+        .weighted_sum
+        .checked_add(prior_sum)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?
+        .checked_mul(100)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+    let denominator = stats
+        .weight_total
+        .checked_add(stats.prior_weight as u128)
+        .ok_or(ErrorCode::RatingAggregateOverflow)?;
+
+    stats.average_times_100 = (numerator / denominator)
+        .try_into()
+        .map_err(|_| ErrorCode::RatingAggregateOverflow)?;
+
+    Ok(())
 }
 
 #[derive(Accounts)]
@@ -130,27 +356,205 @@ pub struct BookTable<'info> {
         bump
     )]
     pub user_stats: Account<'info, UserStats>,
+    #[account(
+        mut,
+        seeds = [b"loyalty", user.key().as_ref(), restaurant.key().as_ref()],
+        bump
+    )]
+    pub loyalty_account: Account<'info, LoyaltyAccount>,
+    /// CHECK: Only used as key
+    pub restaurant: AccountInfo<'info>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeLoyaltyAccount<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + 32 + 32 + 8, // 8 (discriminator) + 32 (pubkey) + 32 (pubkey) + 8 (u64)
+        seeds = [b"loyalty", user.key().as_ref(), restaurant.key().as_ref()],
+        bump
+    )]
+    pub loyalty_account: Account<'info, LoyaltyAccount>,
     /// CHECK: Only used as key
     pub restaurant: AccountInfo<'info>,
     #[account(mut)]
     pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferPoints<'info> {
+    #[account(mut, has_one = user)]
+    pub sender: Account<'info, LoyaltyAccount>,
+    #[account(
+        mut,
+        constraint = sender.key() != receiver.key() @ ErrorCode::SameLoyaltyAccount
+    )]
+    pub receiver: Account<'info, LoyaltyAccount>,
+    pub user: Signer<'info>,
 }
 
 #[derive(Accounts)]
-#[instruction(rating: u8, review: String, confidence_level: u8)]
+pub struct InitializeRestaurantStats<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 32 + 16 + 16 + 8 + 8 + 8, // disc + restaurant + weight_total + weighted_sum + prior_mean + prior_weight + average_times_100
+        seeds = [b"restaurant-stats", restaurant.key().as_ref()],
+        bump
+    )]
+    pub restaurant_stats: Account<'info, RestaurantStats>,
+    /// CHECK: Only used as key
+    pub restaurant: AccountInfo<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(rating: u8, review: String, confidence_level: u8, text_len: u32)]
 pub struct SubmitReview<'info> {
     #[account(
-        init_if_needed,
+        init,
+        payer = user,
+        space = 8 + 32 + 32 + 8 + 4 + 2 + 1 + 1, // disc + user + restaurant + last_updated + text_len + edit_count + rating + confidence_level
+        seeds = [b"review", user.key().as_ref(), restaurant.key().as_ref()],
+        bump
+    )]
+    pub review: AccountLoader<'info, Review>,
+    #[account(
+        init,
         payer = user,
-        space = 8 + 32 + 32 + 1 + 4 + 200 + 1, // Added 1 byte for confidence_level
+        space = 8 + 32 + 4 + text_len as usize, // disc + review key + Vec len prefix + text bytes
+        seeds = [b"review-text", user.key().as_ref(), restaurant.key().as_ref()],
+        bump
+    )]
+    pub review_text: Account<'info, ReviewText>,
+    #[account(
+        mut,
+        seeds = [b"restaurant-stats", restaurant.key().as_ref()],
+        bump
+    )]
+    pub restaurant_stats: Account<'info, RestaurantStats>,
+    /// CHECK: Only used for PDA seed
+    pub restaurant: AccountInfo<'info>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(rating: u8, review: String, confidence_level: u8, text_len: u32)]
+pub struct UpdateReview<'info> {
+    #[account(
+        mut,
+        has_one = user,
+        seeds = [b"review", user.key().as_ref(), restaurant.key().as_ref()],
+        bump
+    )]
+    pub review: AccountLoader<'info, Review>,
+    #[account(
+        mut,
+        seeds = [b"review-text", user.key().as_ref(), restaurant.key().as_ref()],
+        bump,
+        realloc = 8 + 32 + 4 + text_len as usize,
+        realloc::payer = user,
+        realloc::zero = true,
+    )]
+    pub review_text: Account<'info, ReviewText>,
+    #[account(
+        mut,
+        seeds = [b"restaurant-stats", restaurant.key().as_ref()],
+        bump
+    )]
+    pub restaurant_stats: Account<'info, RestaurantStats>,
+    /// CHECK: Only used for PDA seed
+    pub restaurant: AccountInfo<'info>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseReview<'info> {
+    #[account(
+        mut,
+        has_one = user,
+        close = user,
         seeds = [b"review", user.key().as_ref(), restaurant.key().as_ref()],
         bump
     )]
-    pub review: Account<'info, Review>,
+    pub review: AccountLoader<'info, Review>,
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"review-text", user.key().as_ref(), restaurant.key().as_ref()],
+        bump
+    )]
+    pub review_text: Account<'info, ReviewText>,
+    #[account(
+        mut,
+        seeds = [b"restaurant-stats", restaurant.key().as_ref()],
+        bump
+    )]
+    pub restaurant_stats: Account<'info, RestaurantStats>,
     /// CHECK: Only used for PDA seed
     pub restaurant: AccountInfo<'info>,
     #[account(mut)]
     pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FollowUser<'info> {
+    #[account(
+        init_if_needed,
+        payer = follower,
+        space = 8 + 32 + 32 + 1 + 1 + 4 + 32 + 8, // disc + follower + followee + followed + muted + petname_len + petname_data + last_updated
+        seeds = [b"follow", follower.key().as_ref(), followee.key().as_ref()],
+        bump
+    )]
+    pub follow_record: Account<'info, FollowRecord>,
+    /// CHECK: Only used as key
+    pub followee: AccountInfo<'info>,
+    #[account(mut)]
+    pub follower: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPetname<'info> {
+    #[account(
+        init_if_needed,
+        payer = follower,
+        space = 8 + 32 + 32 + 1 + 1 + 4 + 32 + 8,
+        seeds = [b"follow", follower.key().as_ref(), followee.key().as_ref()],
+        bump
+    )]
+    pub follow_record: Account<'info, FollowRecord>,
+    /// CHECK: Only used as key
+    pub followee: AccountInfo<'info>,
+    #[account(mut)]
+    pub follower: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MuteUser<'info> {
+    #[account(
+        init_if_needed,
+        payer = follower,
+        space = 8 + 32 + 32 + 1 + 1 + 4 + 32 + 8,
+        seeds = [b"follow", follower.key().as_ref(), followee.key().as_ref()],
+        bump
+    )]
+    pub follow_record: Account<'info, FollowRecord>,
+    /// CHECK: Only used as key
+    pub followee: AccountInfo<'info>,
+    #[account(mut)]
+    pub follower: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
@@ -170,22 +574,75 @@ pub struct DishStats {
     pub name_data: [u8; 50],
 }
 
-#[account]
+// Zero-copy: kept small and fixed-size so deserializing it never touches the
+// stack-heavy Borsh path. The actual review text lives in `ReviewText` so its
+// length isn't bounded by what fits in this account.
+//
+// Field order matters here: `#[repr(C)]` (added by `zero_copy`) packs fields
+// in declaration order, so the 8-byte-aligned `last_updated` is placed right
+// after the two `Pubkey`s to avoid alignment padding. Reordering this struct
+// changes its size — keep `space` in `SubmitReview` in sync with `size_of::<Review>()`.
+#[account(zero_copy)]
 pub struct Review {
     pub user: Pubkey,
     pub restaurant: Pubkey,
-    pub rating: u8,             // 1-5
-    pub review_len: u32,        // Length of review text
-    pub review_data: [u8; 200], // Max 200 bytes for review text
-    pub confidence_level: u8,   // 1-10, confidence in the review
+    pub last_updated: i64,    // Unix timestamp of the last submit/update
+    pub text_len: u32,        // Length of the text stored in the matching ReviewText account
+    pub edit_count: u16,      // Number of times this review has been updated
+    pub rating: u8,           // 1-5
+    pub confidence_level: u8, // 1-10, confidence in the review
+}
+
+#[account]
+pub struct ReviewText {
+    pub review: Pubkey, // The Review PDA this text belongs to
+    pub data: Vec<u8>,  // UTF-8 review text, sized on submit and realloc'd on update
+}
+
+#[account]
+pub struct LoyaltyAccount {
+    pub user: Pubkey,
+    pub restaurant: Pubkey,
+    pub points: u64,
+}
+
+#[account]
+pub struct RestaurantStats {
+    pub restaurant: Pubkey,
+    pub weight_total: u128,
+    pub weighted_sum: u128,
+    pub prior_mean: u64,        // m: the assumed average before any reviews
+    pub prior_weight: u64,      // C: how many "virtual" prior reviews that's worth
+    pub average_times_100: u64, // cached (weighted_sum + C*m) / (weight_total + C), scaled by 100
+}
+
+#[account]
+pub struct FollowRecord {
+    pub follower: Pubkey,
+    pub followee: Pubkey,
+    pub followed: bool,
+    pub muted: bool,
+    pub petname_len: u32,
+    pub petname_data: [u8; 32], // Max 32 bytes for an optional short petname
+    pub last_updated: i64,
 }
 
 #[error_code]
 pub enum ErrorCode {
     #[msg("Rating must be between 1 and 5")]
     InvalidRating,
-    #[msg("You have already submitted a review for this restaurant.")]
-    ReviewAlreadyExists,
     #[msg("Confidence level must be between 1 and 10")]
     InvalidConfidenceLevel,
+    #[msg("Dish stats account failed PDA or ownership validation")]
+    UnauthorizedDishStats,
+    #[msg("Sender does not have enough loyalty points for this transfer")]
+    InsufficientLoyaltyPoints,
+    #[msg("Loyalty points calculation overflowed")]
+    LoyaltyPointsOverflow,
+    #[msg("Sender and receiver loyalty accounts must be different")]
+    SameLoyaltyAccount,
+    #[msg("Rating aggregate calculation overflowed")]
+    RatingAggregateOverflow,
+    #[msg("text_len does not match the supplied review text length")]
+    TextLengthMismatch,
 }